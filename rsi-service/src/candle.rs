@@ -0,0 +1,108 @@
+//! Rolling OHLCV candle aggregation, running in parallel with RSI.
+//!
+//! Trades are bucketed by `floor(timestamp_ms / interval_ms)`. When a trade
+//! arrives in a new bucket the previous candle is finalized and handed back to
+//! the caller for publishing to the candle topic, keyed by token.
+
+use serde::Serialize;
+
+/// An open, still-accumulating candle for one token.
+#[derive(Debug, Clone)]
+pub struct Candle {
+    bucket: u128,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    trade_count: u64,
+    volume: f64,
+}
+
+/// A finalized candle ready to be published as JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct CandleMessage {
+    pub token_address: String,
+    pub interval_secs: u64,
+    pub bucket_start_ms: u128,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub trade_count: u64,
+    pub volume: f64,
+}
+
+impl Candle {
+    fn new(bucket: u128, price: f64, amount: f64) -> Self {
+        Candle {
+            bucket,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            trade_count: 1,
+            volume: amount,
+        }
+    }
+
+    fn accumulate(&mut self, price: f64, amount: f64) {
+        if price > self.high {
+            self.high = price;
+        }
+        if price < self.low {
+            self.low = price;
+        }
+        self.close = price;
+        self.trade_count += 1;
+        self.volume += amount;
+    }
+
+    fn finalize(&self, token_address: &str, interval_ms: u128) -> CandleMessage {
+        CandleMessage {
+            token_address: token_address.to_string(),
+            interval_secs: (interval_ms / 1000) as u64,
+            bucket_start_ms: self.bucket * interval_ms,
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            trade_count: self.trade_count,
+            volume: self.volume,
+        }
+    }
+}
+
+/// Feed a trade into `current`, returning the previous candle if this trade
+/// opened a new, later bucket. `amount` is the trade volume (0.0 when absent
+/// upstream).
+///
+/// Trades are expected to arrive in non-decreasing `timestamp_ms` order per
+/// token, but with multiple producers/partitions a trade can occasionally
+/// land in a bucket earlier than the one currently open. Such a trade is
+/// folded into the in-progress candle rather than rolling it over, so the
+/// same interval is never finalized and published twice.
+pub fn ingest(
+    current: &mut Option<Candle>,
+    token_address: &str,
+    price: f64,
+    amount: f64,
+    timestamp_ms: u128,
+    interval_ms: u128,
+) -> Option<CandleMessage> {
+    let bucket = timestamp_ms / interval_ms;
+    match current {
+        Some(candle) if bucket <= candle.bucket => {
+            candle.accumulate(price, amount);
+            None
+        }
+        Some(candle) => {
+            let finalized = candle.finalize(token_address, interval_ms);
+            *current = Some(Candle::new(bucket, price, amount));
+            Some(finalized)
+        }
+        None => {
+            *current = Some(Candle::new(bucket, price, amount));
+            None
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Read-only HTTP API over the in-memory indicator state.
+//!
+//! Serves the same [`CheckpointMap`] the WebSocket path streams from, so
+//! downstream tools can poll current values without replaying the Kafka topic:
+//! `GET /rsi/{token_address}` returns the latest [`RsiMessage`], and
+//! `GET /tickers` returns every token with its current RSI and last price.
+
+use crate::ws::CheckpointMap;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use axum::routing::get;
+use axum::Router;
+use log::{error, info};
+use serde::Serialize;
+
+/// One row of the `/tickers` response.
+#[derive(Debug, Serialize)]
+struct Ticker {
+    token_address: String,
+    rsi: f64,
+    price: f64,
+}
+
+/// Build the router wired to the shared checkpoint map.
+pub fn router(checkpoints: CheckpointMap) -> Router {
+    Router::new()
+        .route("/rsi/{token_address}", get(get_rsi))
+        .route("/tickers", get(get_tickers))
+        .with_state(checkpoints)
+}
+
+/// Serve the HTTP API until the listener errors.
+pub async fn run_server(bind: String, checkpoints: CheckpointMap) {
+    let listener = match tokio::net::TcpListener::bind(&bind).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to bind HTTP server on {}: {:?}", bind, e);
+            return;
+        }
+    };
+    info!("HTTP server listening on {}", bind);
+    if let Err(e) = axum::serve(listener, router(checkpoints)).await {
+        error!("HTTP server error: {:?}", e);
+    }
+}
+
+async fn get_rsi(
+    State(checkpoints): State<CheckpointMap>,
+    Path(token_address): Path<String>,
+) -> impl IntoResponse {
+    let latest = checkpoints
+        .lock()
+        .ok()
+        .and_then(|map| map.get(&token_address).cloned());
+
+    match latest {
+        Some(msg) => Json(msg).into_response(),
+        None => (StatusCode::NOT_FOUND, "unknown token").into_response(),
+    }
+}
+
+async fn get_tickers(State(checkpoints): State<CheckpointMap>) -> impl IntoResponse {
+    let tickers: Vec<Ticker> = match checkpoints.lock() {
+        Ok(map) => map
+            .values()
+            .map(|m| Ticker {
+                token_address: m.token_address.clone(),
+                rsi: m.rsi,
+                price: m.price,
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    Json(tickers)
+}
@@ -1,24 +1,42 @@
 use anyhow::Result;
-use futures::StreamExt;
+use async_trait::async_trait;
 use log::{error, info};
 use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
 use rdkafka::message::Message;
 use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::ClientConfig;
+use rdkafka::{ClientConfig, Offset, TopicPartitionList};
 use serde::Serialize;
-use serde_json::Value;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::env;
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::signal;
 
+mod candle;
+mod http;
+mod metrics;
+mod pipeline;
+mod ws;
+
+use pipeline::{
+    process_trade, DlqRecord, PipelineConfig, PipelineState, RawRecord, RsiSink, SourceEvent,
+    TradeSource,
+};
+use ws::{CheckpointMap, PeerMap};
+
 const DEFAULT_BROKER: &str = "localhost:29092";
 const DEFAULT_TRADE_TOPIC: &str = "trade-data";
 const DEFAULT_RSI_TOPIC: &str = "rsi-data";
 const DEFAULT_PERIOD: usize = 14;
-const MAX_HISTORY: usize = 200; // keep recent prices per token (memory bound)
-
-#[derive(Debug, Serialize)]
+const DEFAULT_DLQ_TOPIC: &str = "trade-dlq";
+const DEFAULT_DLQ_MAX_INVALID_PER_MIN: u64 = 600;
+const DEFAULT_WS_BIND: &str = "127.0.0.1:9001";
+const DEFAULT_CANDLE_TOPIC: &str = "candle-data";
+const DEFAULT_CANDLE_INTERVAL_SECS: u64 = 60;
+const DEFAULT_METRICS_FLUSH_SECS: u64 = 10;
+const DEFAULT_HTTP_BIND: &str = "127.0.0.1:8080";
+
+#[derive(Debug, Clone, Serialize)]
 struct RsiMessage {
     token_address: String,
     rsi: f64,
@@ -33,73 +51,147 @@ fn now_ms() -> u128 {
         .unwrap_or_default()
 }
 
-/// compute RSI using simple (Wilder-ish) SMA over last (period + 1) prices.
-/// returns None if not enough prices.
-fn compute_rsi_from_prices(prices: &VecDeque<f64>, period: usize) -> Option<f64> {
-    if prices.len() < period + 1 {
-        return None;
-    }
-
-    let start = prices.len() - (period + 1);
-    let slice: Vec<f64> = prices.iter().skip(start).cloned().collect();
+/// Spawn a fire-and-forget task producing `payload` to `topic` keyed by `key`.
+fn spawn_produce(
+    producer: &FutureProducer,
+    metrics: &Arc<metrics::Metrics>,
+    topic: &str,
+    key: &str,
+    payload: String,
+) {
+    let producer = producer.clone();
+    let metrics = metrics.clone();
+    let topic = topic.to_string();
+    let key = key.to_string();
+    tokio::spawn(async move {
+        let delivery = producer
+            .send(
+                FutureRecord::to(&topic).payload(&payload).key(&key),
+                Some(Duration::from_secs(5)),
+            )
+            .await;
+        match delivery {
+            Ok((partition, offset)) => {
+                info!("message delivered to {} partition {} offset {}", topic, partition, offset)
+            }
+            Err((kafka_err, _owned_msg)) => {
+                metrics.inc_produce_failures();
+                error!("Failed to deliver message to {}: {:?}", topic, kafka_err)
+            }
+        }
+    });
+}
 
-    let mut gain_sum = 0.0;
-    let mut loss_sum = 0.0;
+/// rdkafka-backed [`TradeSource`]: pulls one message at a time and commits
+/// offsets explicitly (`enable.auto.commit = false`).
+struct KafkaTradeSource {
+    consumer: StreamConsumer,
+}
 
-    for i in 1..slice.len() {
-        let d = slice[i] - slice[i - 1];
-        if d > 0.0 {
-            gain_sum += d;
-        } else {
-            loss_sum += -d;
+#[async_trait]
+impl TradeSource for KafkaTradeSource {
+    async fn next(&mut self) -> SourceEvent {
+        match self.consumer.recv().await {
+            Ok(msg) => SourceEvent::Record(RawRecord {
+                payload: msg.payload().map(|b| b.to_vec()),
+                topic: msg.topic().to_string(),
+                partition: msg.partition(),
+                offset: msg.offset(),
+            }),
+            Err(e) => {
+                // Transient broker/partition errors (the kind
+                // reconnect.backoff.ms/session.timeout.ms are tuned to
+                // tolerate) must not be mistaken for the stream ending.
+                error!("Kafka error while consuming: {:?}", e);
+                SourceEvent::Error
+            }
         }
     }
 
-    let avg_gain = gain_sum / (period as f64);
-    let avg_loss = loss_sum / (period as f64);
-
-    if avg_loss == 0.0 {
-        return Some(100.0);
+    async fn commit(&mut self, rec: &RawRecord) {
+        let mut tpl = TopicPartitionList::new();
+        if let Err(e) =
+            tpl.add_partition_offset(&rec.topic, rec.partition, Offset::Offset(rec.offset + 1))
+        {
+            error!("Failed to build commit offset list: {:?}", e);
+            return;
+        }
+        if let Err(e) = self.consumer.commit(&tpl, CommitMode::Async) {
+            error!("Failed to commit message: {:?}", e);
+        }
     }
+}
 
-    let rs = avg_gain / avg_loss;
-    let rsi = 100.0 - (100.0 / (1.0 + rs));
-    Some(rsi)
+/// rdkafka-backed [`RsiSink`]: produces RSI/candle messages, fans RSI out to
+/// WebSocket subscribers, and dead-letters invalid messages before commit.
+struct KafkaRsiSink {
+    producer: FutureProducer,
+    metrics: Arc<metrics::Metrics>,
+    checkpoints: CheckpointMap,
+    peers: PeerMap,
+    rsi_topic: String,
+    candle_topic: String,
+    dlq_topic: String,
 }
 
-/// try to extract (token_address, price) from a JSON payload string
-fn parse_price_from_payload(payload: &str) -> Option<(String, f64)> {
-    match serde_json::from_str::<Value>(payload) {
-        Ok(v) => {
-            let token = v
-                .get("token_address")
-                .and_then(|t| t.as_str())
-                .map(|s| s.to_string());
+#[async_trait]
+impl RsiSink for KafkaRsiSink {
+    async fn produce_rsi(&self, token_address: &str, rsi: f64, price: f64) {
+        let rsi_msg = RsiMessage {
+            token_address: token_address.to_string(),
+            rsi,
+            price,
+            timestamp_ms: now_ms(),
+        };
+        self.metrics.inc_rsi_emitted();
+        self.metrics.set_last_rsi(token_address, rsi);
+        ws::broadcast(&self.checkpoints, &self.peers, &rsi_msg);
+        match serde_json::to_string(&rsi_msg) {
+            Ok(s) => spawn_produce(&self.producer, &self.metrics, &self.rsi_topic, token_address, s),
+            Err(e) => error!("Failed to serialize RSI message: {:?}", e),
+        }
+    }
 
-            let price_field_candidates = ["price_in_sol", "price", "price_sol", "amount_in_sol"];
-            let mut price_opt: Option<f64> = None;
+    async fn produce_candle(&self, candle: &candle::CandleMessage) {
+        match serde_json::to_string(candle) {
+            Ok(s) => spawn_produce(
+                &self.producer,
+                &self.metrics,
+                &self.candle_topic,
+                &candle.token_address,
+                s,
+            ),
+            Err(e) => error!("Failed to serialize candle message: {:?}", e),
+        }
+    }
 
-            for field in price_field_candidates.iter() {
-                if let Some(pv) = v.get(*field) {
-                    if pv.is_string() {
-                        if let Ok(parsed) = pv.as_str().unwrap_or_default().parse::<f64>() {
-                            price_opt = Some(parsed);
-                            break;
-                        }
-                    } else if pv.is_number() {
-                        price_opt = pv.as_f64();
-                        break;
-                    }
-                }
+    async fn dead_letter(&self, rec: &DlqRecord) -> bool {
+        let payload = match rec.to_envelope_json() {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to serialize DLQ envelope: {:?}", e);
+                return false;
             }
-
-            if let (Some(token_addr), Some(price)) = (token, price_opt) {
-                Some((token_addr, price))
-            } else {
-                None
+        };
+        let key = format!("{}:{}", rec.source_topic, rec.partition);
+        let delivery = self
+            .producer
+            .send(
+                FutureRecord::to(&self.dlq_topic).payload(&payload).key(&key),
+                Some(Duration::from_secs(5)),
+            )
+            .await;
+        match delivery {
+            Ok((p, o)) => {
+                info!("DLQ message delivered to partition {} offset {}", p, o);
+                true
+            }
+            Err((kafka_err, _owned_msg)) => {
+                self.metrics.inc_produce_failures();
+                error!("Failed to deliver DLQ message: {:?}", kafka_err);
+                false
             }
         }
-        Err(_) => None,
     }
 }
 
@@ -115,9 +207,28 @@ async fn main() -> Result<()> {
         .and_then(|s| s.parse().ok())
         .unwrap_or(DEFAULT_PERIOD);
 
+    let dlq_topic = env::var("DLQ_TOPIC").unwrap_or_else(|_| DEFAULT_DLQ_TOPIC.to_string());
+    let dlq_max_invalid_per_min: u64 = env::var("DLQ_MAX_INVALID_PER_MIN")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_DLQ_MAX_INVALID_PER_MIN);
+
+    let candle_topic = env::var("CANDLE_TOPIC").unwrap_or_else(|_| DEFAULT_CANDLE_TOPIC.to_string());
+    let candle_interval_secs: u64 = env::var("CANDLE_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&s| s > 0)
+        .unwrap_or(DEFAULT_CANDLE_INTERVAL_SECS);
+    let candle_interval_ms = candle_interval_secs as u128 * 1000;
+    // drive RSI from candle closes ("candle") or from every raw tick ("tick")
+    let rsi_on_candle = env::var("RSI_SOURCE")
+        .map(|s| s.eq_ignore_ascii_case("candle"))
+        .unwrap_or(false);
+
     info!(
-        "Starting RSI service. broker={} trade_topic={} rsi_topic={} period={}",
-        broker, trade_topic, rsi_topic, period
+        "Starting RSI service. broker={} trade_topic={} rsi_topic={} period={} dlq_topic={} dlq_max_invalid_per_min={} candle_topic={} candle_interval_secs={} rsi_source={}",
+        broker, trade_topic, rsi_topic, period, dlq_topic, dlq_max_invalid_per_min, candle_topic, candle_interval_secs,
+        if rsi_on_candle { "candle" } else { "tick" }
     );
 
     let group_id = std::env::var("GROUP_ID").unwrap_or_else(|_| "rsi-service".to_string());
@@ -138,7 +249,6 @@ async fn main() -> Result<()> {
         .set("socket.keepalive.enable", "true")
         .create()?;
 
-
     // Subscribe to trade topic
     consumer.subscribe(&[&trade_topic])?;
 
@@ -150,12 +260,50 @@ async fn main() -> Result<()> {
         .set("retry.backoff.ms", "1000")
         .create()?;
 
+    // metrics subsystem with a background flush task
+    let metrics = metrics::Metrics::new();
+    let metrics_flush_secs: u64 = env::var("METRICS_FLUSH_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_METRICS_FLUSH_SECS);
+    let metrics_backend = metrics::Backend::from_env(
+        env::var("METRICS_BACKEND").ok(),
+        env::var("STATSD_ADDR").ok(),
+    );
+    metrics::spawn_flusher(metrics.clone(), metrics_backend, metrics_flush_secs);
+
+    // shared state for the WebSocket streaming server
+    let ws_bind = env::var("WS_BIND").unwrap_or_else(|_| DEFAULT_WS_BIND.to_string());
+    let checkpoints: CheckpointMap = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    let peers: PeerMap = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    {
+        let checkpoints = checkpoints.clone();
+        let peers = peers.clone();
+        tokio::spawn(ws::run_server(ws_bind, checkpoints, peers));
+    }
 
-    // per-token history buffer
-    let mut history: HashMap<String, VecDeque<f64>> = HashMap::new();
-
-    // message stream
-    let mut message_stream = consumer.stream();
+    // read-only HTTP API over the same checkpoint map
+    let http_bind = env::var("HTTP_BIND").unwrap_or_else(|_| DEFAULT_HTTP_BIND.to_string());
+    tokio::spawn(http::run_server(http_bind, checkpoints.clone()));
+
+    // wire up the broker-agnostic pipeline against the rdkafka source/sink
+    let cfg = PipelineConfig {
+        period,
+        candle_interval_ms,
+        rsi_on_candle,
+        dlq_max_invalid_per_min,
+    };
+    let mut state = PipelineState::new(dlq_max_invalid_per_min, now_ms());
+    let mut source = KafkaTradeSource { consumer };
+    let sink = KafkaRsiSink {
+        producer,
+        metrics: metrics.clone(),
+        checkpoints,
+        peers,
+        rsi_topic,
+        candle_topic,
+        dlq_topic,
+    };
 
     // ctrl-c signal future (pinned so tokio::select! can use &mut)
     let sig = signal::ctrl_c();
@@ -163,83 +311,22 @@ async fn main() -> Result<()> {
 
     loop {
         tokio::select! {
-            maybe_msg = message_stream.next() => {
-                match maybe_msg {
-                    Some(Ok(msg)) => {
-                        // payload_view returns Option<Result<&str, Utf8Error>> for this rdkafka version
-                        match msg.payload_view::<str>() {
-                            Some(Ok(payload)) => {
-                                if let Some((token_addr, price)) = parse_price_from_payload(payload) {
-                                    let entry = history.entry(token_addr.clone()).or_insert_with(|| VecDeque::with_capacity(MAX_HISTORY));
-                                    entry.push_back(price);
-                                    if entry.len() > MAX_HISTORY {
-                                        entry.pop_front();
-                                    }
-
-                                    if let Some(rsi) = compute_rsi_from_prices(entry, period) {
-                                        let rsi_msg = RsiMessage {
-                                            token_address: token_addr.clone(),
-                                            rsi,
-                                            price,
-                                            timestamp_ms: now_ms(),
-                                        };
-
-                                        // Serialize payload now (owned String)
-                                        let payload_string = match serde_json::to_string(&rsi_msg) {
-                                            Ok(s) => s,
-                                            Err(e) => {
-                                                error!("Failed to serialize RSI message: {:?}", e);
-                                                continue;
-                                            }
-                                        };
-
-                                        // CLONE or move owned values into the spawned task so they are 'static
-                                        // FutureProducer implements Clone (cheap), String clones are owned.
-                                        let producer_cloned = producer.clone();
-                                        let rsi_topic_cloned = rsi_topic.clone();
-                                        let token_cloned = token_addr.clone();
-                                        let payload_cloned = payload_string; // move ownership
-
-                                        // spawn task that owns everything it needs
-                                        tokio::spawn(async move {
-                                            let produce_future = producer_cloned.send(
-                                                FutureRecord::to(&rsi_topic_cloned)
-                                                    .payload(&payload_cloned)
-                                                    .key(&token_cloned),
-                                                Some(Duration::from_secs(5)),
-                                            );
-
-                                            match produce_future.await {
-                                                Ok((partition, offset)) => {
-                                                    info!("RSI message delivered to partition {} offset {}", partition, offset);
-                                                }
-                                                Err((kafka_err, _owned_msg)) => {
-                                                    error!("Failed to deliver RSI message: {:?}", kafka_err);
-                                                }
-                                            }
-                                        });
-                                    }
-                                } else {
-                                    error!("Could not parse token/price from payload: {}", payload);
-                                }
-                            }
-                            Some(Err(e)) => {
-                                error!("Payload present but not valid UTF-8: {:?}", e);
-                            }
-                            None => {
-                                // no payload
-                            }
+            event = source.next() => {
+                match event {
+                    SourceEvent::Record(rec) => {
+                        metrics.inc_trades_consumed();
+                        let outcome = process_trade(&mut state, &cfg, &metrics, &sink, &rec).await;
+                        if outcome.exit {
+                            std::process::exit(1);
                         }
-
-                        // commit offset (at least once)
-                        if let Err(e) = consumer.commit_message(&msg, CommitMode::Async) {
-                            error!("Failed to commit message: {:?}", e);
+                        if outcome.commit {
+                            source.commit(&rec).await;
                         }
                     }
-                    Some(Err(e)) => {
-                        error!("Kafka error while consuming: {:?}", e);
+                    SourceEvent::Error => {
+                        // Recoverable (e.g. a transient broker hiccup); keep polling.
                     }
-                    None => {
+                    SourceEvent::Ended => {
                         info!("Consumer stream ended.");
                         break;
                     }
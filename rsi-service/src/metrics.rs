@@ -0,0 +1,134 @@
+//! Lightweight metrics subsystem.
+//!
+//! Counters and per-token gauges are kept in atomics/a small map and flushed by
+//! a background task every `flush_secs` to either stdout or a statsd endpoint,
+//! selected by the `METRICS_BACKEND` / `STATSD_ADDR` env vars. This gives the
+//! service the same throughput/failure visibility the fill and connector
+//! services expose.
+
+use log::{error, info};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Where flushed metrics are written.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    Stdout,
+    /// statsd over UDP at the given `host:port`.
+    Statsd(String),
+}
+
+impl Backend {
+    /// Resolve the backend from `METRICS_BACKEND`/`STATSD_ADDR`-style inputs,
+    /// defaulting to stdout when unset or unrecognized.
+    pub fn from_env(backend: Option<String>, statsd_addr: Option<String>) -> Self {
+        match backend.as_deref() {
+            Some("statsd") => Backend::Statsd(
+                statsd_addr.unwrap_or_else(|| "127.0.0.1:8125".to_string()),
+            ),
+            _ => Backend::Stdout,
+        }
+    }
+}
+
+/// Process-wide counters and gauges, shared behind an `Arc`.
+#[derive(Default)]
+pub struct Metrics {
+    pub trades_consumed: AtomicU64,
+    pub rsi_emitted: AtomicU64,
+    pub parse_failures: AtomicU64,
+    pub produce_failures: AtomicU64,
+    /// Latest RSI per token, flushed as individual gauges.
+    last_rsi: Mutex<HashMap<String, f64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Metrics::default())
+    }
+
+    pub fn inc_trades_consumed(&self) {
+        self.trades_consumed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_rsi_emitted(&self) {
+        self.rsi_emitted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_parse_failures(&self) {
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_produce_failures(&self) {
+        self.produce_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_last_rsi(&self, token: &str, rsi: f64) {
+        if let Ok(mut map) = self.last_rsi.lock() {
+            map.insert(token.to_string(), rsi);
+        }
+    }
+}
+
+/// Spawn the background flush loop. Returns immediately.
+pub fn spawn_flusher(metrics: Arc<Metrics>, backend: Backend, flush_secs: u64) {
+    tokio::spawn(async move {
+        let socket = match &backend {
+            Backend::Statsd(_) => match UdpSocket::bind("0.0.0.0:0").await {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    error!("Failed to open statsd UDP socket: {:?}", e);
+                    None
+                }
+            },
+            Backend::Stdout => None,
+        };
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(flush_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            flush(&metrics, &backend, socket.as_ref()).await;
+        }
+    });
+}
+
+async fn flush(metrics: &Metrics, backend: &Backend, socket: Option<&UdpSocket>) {
+    let trades = metrics.trades_consumed.load(Ordering::Relaxed);
+    let rsi = metrics.rsi_emitted.load(Ordering::Relaxed);
+    let parse = metrics.parse_failures.load(Ordering::Relaxed);
+    let produce = metrics.produce_failures.load(Ordering::Relaxed);
+    let last_rsi = metrics
+        .last_rsi
+        .lock()
+        .map(|m| m.clone())
+        .unwrap_or_default();
+
+    match backend {
+        Backend::Stdout => {
+            info!(
+                "metrics trades_consumed={} rsi_emitted={} parse_failures={} produce_failures={} tokens={}",
+                trades, rsi, parse, produce, last_rsi.len()
+            );
+            for (token, value) in &last_rsi {
+                info!("metrics last_rsi token={} value={:.4}", token, value);
+            }
+        }
+        Backend::Statsd(addr) => {
+            if let Some(socket) = socket {
+                let mut lines = format!(
+                    "trades_consumed:{}|g\nrsi_emitted:{}|g\nparse_failures:{}|g\nproduce_failures:{}|g\n",
+                    trades, rsi, parse, produce
+                );
+                for (token, value) in &last_rsi {
+                    lines.push_str(&format!("last_rsi.{}:{}|g\n", token, value));
+                }
+                if let Err(e) = socket.send_to(lines.as_bytes(), addr).await {
+                    error!("Failed to send metrics to statsd {}: {:?}", addr, e);
+                }
+            }
+        }
+    }
+}
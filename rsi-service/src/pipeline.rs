@@ -0,0 +1,600 @@
+//! Broker-agnostic consume → compute → produce pipeline.
+//!
+//! The indicator logic used to be welded into `main` and could only run
+//! against a live Kafka. Here it is expressed against a [`TradeSource`] /
+//! [`RsiSink`] pair: the rdkafka consumer/producer are one implementation, and
+//! an in-memory broker (a `VecDeque` queue plus a capturing sink) is another,
+//! so unit tests can feed synthetic trade sequences and assert the exact RSI,
+//! candle, and dead-letter routing without a broker.
+
+use crate::candle::{self, Candle, CandleMessage};
+use crate::metrics::Metrics;
+use async_trait::async_trait;
+use log::error;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Compute RSI once avg gain/loss are known. Emits 100 when there are no losses.
+pub fn rsi_from_averages(avg_gain: f64, avg_loss: f64) -> f64 {
+    if avg_loss == 0.0 {
+        return 100.0;
+    }
+    100.0 - (100.0 / (1.0 + avg_gain / avg_loss))
+}
+
+/// Incremental Wilder RSI state for a single token.
+///
+/// Seeding accumulates the first `period` price deltas as simple sums; once
+/// `count == period` the seed averages are set and the first RSI is emitted.
+/// Every later tick applies Wilder smoothing in O(1), so we only ever keep a
+/// handful of floats per token instead of a price buffer.
+pub struct RsiState {
+    prev_price: f64,
+    avg_gain: f64,
+    avg_loss: f64,
+    count: usize,
+}
+
+impl RsiState {
+    pub fn new() -> Self {
+        RsiState {
+            // NaN marks "no baseline price yet"; the first tick just seeds it.
+            prev_price: f64::NAN,
+            avg_gain: 0.0,
+            avg_loss: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Feed the next price and return the RSI once enough deltas have been seen.
+    /// Returns `None` while still seeding (fewer than `period` deltas).
+    pub fn update(&mut self, price: f64, period: usize) -> Option<f64> {
+        if self.prev_price.is_nan() {
+            self.prev_price = price;
+            return None;
+        }
+
+        let delta = price - self.prev_price;
+        self.prev_price = price;
+        let gain = if delta > 0.0 { delta } else { 0.0 };
+        let loss = if delta < 0.0 { -delta } else { 0.0 };
+
+        if self.count < period {
+            // Still seeding: accumulate simple sums in avg_gain/avg_loss.
+            self.avg_gain += gain;
+            self.avg_loss += loss;
+            self.count += 1;
+            if self.count == period {
+                self.avg_gain /= period as f64;
+                self.avg_loss /= period as f64;
+                return Some(rsi_from_averages(self.avg_gain, self.avg_loss));
+            }
+            return None;
+        }
+
+        let p = period as f64;
+        self.avg_gain = (self.avg_gain * (p - 1.0) + gain) / p;
+        self.avg_loss = (self.avg_loss * (p - 1.0) + loss) / p;
+        Some(rsi_from_averages(self.avg_gain, self.avg_loss))
+    }
+}
+
+impl Default for RsiState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A single parsed trade: token, price, event time, and optional volume.
+#[derive(Debug, Clone)]
+pub struct Trade {
+    pub token_address: String,
+    pub price: f64,
+    pub timestamp_ms: u128,
+    pub amount: f64,
+}
+
+/// Pull the first present numeric field (number or numeric string) off a JSON
+/// object, trying each candidate name in order.
+fn first_numeric_field(v: &Value, candidates: &[&str]) -> Option<f64> {
+    for field in candidates.iter() {
+        if let Some(pv) = v.get(*field) {
+            if pv.is_string() {
+                if let Ok(parsed) = pv.as_str().unwrap_or_default().parse::<f64>() {
+                    return Some(parsed);
+                }
+            } else if pv.is_number() {
+                return pv.as_f64();
+            }
+        }
+    }
+    None
+}
+
+/// Parse a full [`Trade`] from a JSON payload string, extracting the token,
+/// price, event timestamp (defaulting to now when absent), and trade volume
+/// (defaulting to 0.0 when no `amount`-style field is present).
+pub fn parse_trade_from_payload(payload: &str) -> Option<Trade> {
+    let v = serde_json::from_str::<Value>(payload).ok()?;
+
+    let token = v
+        .get("token_address")
+        .and_then(|t| t.as_str())
+        .map(|s| s.to_string())?;
+
+    let price = first_numeric_field(&v, &["price_in_sol", "price", "price_sol", "amount_in_sol"])?;
+
+    let timestamp_ms = first_numeric_field(&v, &["timestamp_ms", "timestamp", "block_time", "time"])
+        .map(|t| t as u128)
+        .unwrap_or_else(crate::now_ms);
+
+    let amount = first_numeric_field(&v, &["amount", "volume", "size", "quantity"]).unwrap_or(0.0);
+
+    Some(Trade {
+        token_address: token,
+        price,
+        timestamp_ms,
+        amount,
+    })
+}
+
+/// Envelope written to the dead-letter topic when a trade message cannot be
+/// parsed or decoded. `raw_payload` carries the original bytes verbatim: UTF-8
+/// payloads go through as-is (`encoding = "utf8"`), non-UTF-8 payloads are
+/// base64-encoded (`encoding = "base64"`) so the envelope stays valid JSON.
+#[derive(Debug, Serialize)]
+struct DlqEnvelope<'a> {
+    reason: &'a str,
+    encoding: &'a str,
+    raw_payload: String,
+    source_topic: &'a str,
+    partition: i32,
+    offset: i64,
+    timestamp_ms: u128,
+}
+
+/// A message that failed parsing/decoding, routed to the dead-letter sink.
+#[derive(Debug, Clone)]
+pub struct DlqRecord {
+    pub reason: &'static str,
+    pub utf8: bool,
+    pub raw: Vec<u8>,
+    pub source_topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+impl DlqRecord {
+    /// Serialize this record into the JSON envelope written to the DLQ topic.
+    pub fn to_envelope_json(&self) -> Result<String, serde_json::Error> {
+        use base64::Engine;
+        let (encoding, raw_payload) = if self.utf8 {
+            ("utf8", String::from_utf8_lossy(&self.raw).into_owned())
+        } else {
+            (
+                "base64",
+                base64::engine::general_purpose::STANDARD.encode(&self.raw),
+            )
+        };
+        let envelope = DlqEnvelope {
+            reason: self.reason,
+            encoding,
+            raw_payload,
+            source_topic: &self.source_topic,
+            partition: self.partition,
+            offset: self.offset,
+            timestamp_ms: crate::now_ms(),
+        };
+        serde_json::to_string(&envelope)
+    }
+}
+
+/// Fixed one-minute window counter guarding the DLQ against a flood of invalid
+/// messages (e.g. an upstream schema change). `record` returns true once more
+/// than `max_per_min` invalid messages have been seen in the current window.
+pub struct InvalidRateLimiter {
+    max_per_min: u64,
+    window_start_ms: u128,
+    count: u64,
+}
+
+impl InvalidRateLimiter {
+    pub fn new(max_per_min: u64, now: u128) -> Self {
+        InvalidRateLimiter {
+            max_per_min,
+            window_start_ms: now,
+            count: 0,
+        }
+    }
+
+    /// Record one invalid message and report whether the threshold is exceeded.
+    /// A `max_per_min` of 0 disables the guard.
+    pub fn record(&mut self, now: u128) -> bool {
+        if now.saturating_sub(self.window_start_ms) >= 60_000 {
+            self.window_start_ms = now;
+            self.count = 0;
+        }
+        self.count += 1;
+        self.max_per_min != 0 && self.count > self.max_per_min
+    }
+}
+
+/// A raw trade record as pulled from a source, decoupled from rdkafka's
+/// borrowed message lifetime.
+#[derive(Debug, Clone)]
+pub struct RawRecord {
+    pub payload: Option<Vec<u8>>,
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// Outcome of polling a [`TradeSource`] for its next event.
+#[derive(Debug)]
+pub enum SourceEvent {
+    /// A record was received and is ready for processing.
+    Record(RawRecord),
+    /// A recoverable error occurred while polling (e.g. a transient broker
+    /// hiccup); the source should keep being polled.
+    Error,
+    /// The source is exhausted and will not yield further records. The
+    /// Kafka-backed source never emits this (`recv()` has no end-of-stream
+    /// signal); it's reachable through other `TradeSource` implementations
+    /// such as the in-memory test harness.
+    #[allow(dead_code)]
+    Ended,
+}
+
+/// A stream of raw trade records with explicit offset commits.
+#[async_trait]
+pub trait TradeSource {
+    async fn next(&mut self) -> SourceEvent;
+    async fn commit(&mut self, rec: &RawRecord);
+}
+
+/// Destination for computed indicators and dead-lettered messages.
+#[async_trait]
+pub trait RsiSink {
+    async fn produce_rsi(&self, token_address: &str, rsi: f64, price: f64);
+    async fn produce_candle(&self, candle: &CandleMessage);
+    /// Returns true when the dead-letter write succeeded (offset safe to commit).
+    async fn dead_letter(&self, rec: &DlqRecord) -> bool;
+}
+
+/// Static configuration for the pipeline.
+pub struct PipelineConfig {
+    pub period: usize,
+    pub candle_interval_ms: u128,
+    pub rsi_on_candle: bool,
+    pub dlq_max_invalid_per_min: u64,
+}
+
+/// Mutable per-token indicator state carried across trades.
+pub struct PipelineState {
+    history: HashMap<String, RsiState>,
+    candles: HashMap<String, Option<Candle>>,
+    limiter: InvalidRateLimiter,
+}
+
+impl PipelineState {
+    pub fn new(dlq_max_invalid_per_min: u64, now: u128) -> Self {
+        PipelineState {
+            history: HashMap::new(),
+            candles: HashMap::new(),
+            limiter: InvalidRateLimiter::new(dlq_max_invalid_per_min, now),
+        }
+    }
+}
+
+/// What the loop should do with a record after processing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessOutcome {
+    /// Commit the source offset (false when a DLQ write failed).
+    pub commit: bool,
+    /// Stop consuming and exit non-zero (invalid-message rate exceeded).
+    pub exit: bool,
+}
+
+/// Process a single raw record: decode, parse, roll the candle, update RSI, and
+/// route invalid messages to the dead-letter sink.
+pub async fn process_trade<S: RsiSink + ?Sized>(
+    state: &mut PipelineState,
+    cfg: &PipelineConfig,
+    metrics: &Arc<Metrics>,
+    sink: &S,
+    rec: &RawRecord,
+) -> ProcessOutcome {
+    let bytes = match &rec.payload {
+        Some(b) => b,
+        None => return ProcessOutcome { commit: true, exit: false },
+    };
+
+    match std::str::from_utf8(bytes) {
+        Ok(payload) => match parse_trade_from_payload(payload) {
+            Some(trade) => {
+                handle_valid(state, cfg, sink, trade).await;
+                ProcessOutcome { commit: true, exit: false }
+            }
+            None => {
+                error!("Could not parse token/price from payload: {}", payload);
+                route_invalid(state, cfg, metrics, sink, rec, true, "parse_failure").await
+            }
+        },
+        Err(e) => {
+            error!("Payload present but not valid UTF-8: {:?}", e);
+            route_invalid(state, cfg, metrics, sink, rec, false, "utf8_decode_failure").await
+        }
+    }
+}
+
+async fn handle_valid<S: RsiSink + ?Sized>(
+    state: &mut PipelineState,
+    cfg: &PipelineConfig,
+    sink: &S,
+    trade: Trade,
+) {
+    let token = trade.token_address.clone();
+
+    // roll the OHLCV candle; publish the previous one on bucket roll-over
+    let current = state.candles.entry(token.clone()).or_insert(None);
+    if let Some(finalized) = candle::ingest(
+        current,
+        &token,
+        trade.price,
+        trade.amount,
+        trade.timestamp_ms,
+        cfg.candle_interval_ms,
+    ) {
+        sink.produce_candle(&finalized).await;
+
+        // optionally drive RSI off the candle close for smoother signals
+        if cfg.rsi_on_candle {
+            let entry = state.history.entry(token.clone()).or_default();
+            if let Some(rsi) = entry.update(finalized.close, cfg.period) {
+                sink.produce_rsi(&token, rsi, finalized.close).await;
+            }
+        }
+    }
+
+    if !cfg.rsi_on_candle {
+        let entry = state.history.entry(token.clone()).or_default();
+        if let Some(rsi) = entry.update(trade.price, cfg.period) {
+            sink.produce_rsi(&token, rsi, trade.price).await;
+        }
+    }
+}
+
+async fn route_invalid<S: RsiSink + ?Sized>(
+    state: &mut PipelineState,
+    cfg: &PipelineConfig,
+    metrics: &Arc<Metrics>,
+    sink: &S,
+    rec: &RawRecord,
+    utf8: bool,
+    reason: &'static str,
+) -> ProcessOutcome {
+    metrics.inc_parse_failures();
+    if state.limiter.record(crate::now_ms()) {
+        error!(
+            "Invalid-message rate exceeded {} per minute; stopping to avoid flooding the DLQ",
+            cfg.dlq_max_invalid_per_min
+        );
+        return ProcessOutcome { commit: false, exit: true };
+    }
+
+    let dlq = DlqRecord {
+        reason,
+        utf8,
+        raw: rec.payload.clone().unwrap_or_default(),
+        source_topic: rec.topic.clone(),
+        partition: rec.partition,
+        offset: rec.offset,
+    };
+    let committed = sink.dead_letter(&dlq).await;
+    ProcessOutcome { commit: committed, exit: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RsiMessage;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// In-memory broker: a queue of raw records plus captured commits.
+    struct MemoryTradeSource {
+        queue: VecDeque<RawRecord>,
+        pub committed: Vec<i64>,
+    }
+
+    impl MemoryTradeSource {
+        fn from_payloads(payloads: &[&str]) -> Self {
+            let queue = payloads
+                .iter()
+                .enumerate()
+                .map(|(i, p)| RawRecord {
+                    payload: Some(p.as_bytes().to_vec()),
+                    topic: "trade-data".to_string(),
+                    partition: 0,
+                    offset: i as i64,
+                })
+                .collect();
+            MemoryTradeSource {
+                queue,
+                committed: Vec::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl TradeSource for MemoryTradeSource {
+        async fn next(&mut self) -> SourceEvent {
+            match self.queue.pop_front() {
+                Some(rec) => SourceEvent::Record(rec),
+                None => SourceEvent::Ended,
+            }
+        }
+        async fn commit(&mut self, rec: &RawRecord) {
+            self.committed.push(rec.offset);
+        }
+    }
+
+    /// Capturing sink: records everything produced for later assertions.
+    #[derive(Default)]
+    struct MemoryRsiSink {
+        rsi: Mutex<Vec<RsiMessage>>,
+        candles: Mutex<Vec<CandleMessage>>,
+        dlq: Mutex<Vec<DlqRecord>>,
+    }
+
+    #[async_trait]
+    impl RsiSink for MemoryRsiSink {
+        async fn produce_rsi(&self, token_address: &str, rsi: f64, price: f64) {
+            self.rsi.lock().unwrap().push(RsiMessage {
+                token_address: token_address.to_string(),
+                rsi,
+                price,
+                timestamp_ms: 0,
+            });
+        }
+        async fn produce_candle(&self, candle: &CandleMessage) {
+            self.candles.lock().unwrap().push(candle.clone());
+        }
+        async fn dead_letter(&self, rec: &DlqRecord) -> bool {
+            self.dlq.lock().unwrap().push(rec.clone());
+            true
+        }
+    }
+
+    fn cfg(period: usize) -> PipelineConfig {
+        PipelineConfig {
+            period,
+            candle_interval_ms: 60_000,
+            rsi_on_candle: false,
+            dlq_max_invalid_per_min: 600,
+        }
+    }
+
+    fn trade(price: f64, ts: u128) -> String {
+        format!(
+            "{{\"token_address\":\"TKN\",\"price\":{},\"timestamp_ms\":{}}}",
+            price, ts
+        )
+    }
+
+    async fn drive(source: &mut MemoryTradeSource, sink: &MemoryRsiSink, cfg: &PipelineConfig) {
+        let metrics = Metrics::new();
+        let mut state = PipelineState::new(cfg.dlq_max_invalid_per_min, 0);
+        loop {
+            match source.next().await {
+                SourceEvent::Record(rec) => {
+                    let outcome = process_trade(&mut state, cfg, &metrics, sink, &rec).await;
+                    if outcome.commit {
+                        source.commit(&rec).await;
+                    }
+                }
+                SourceEvent::Error => continue,
+                SourceEvent::Ended => break,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn wilder_rsi_matches_reference_sequence() {
+        // Classic Wilder worked example: 14-period RSI over these closes.
+        let closes = [
+            44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08, 45.89, 46.03,
+            45.61, 46.28, 46.28,
+        ];
+        let mut st = RsiState::new();
+        let mut last = None;
+        for c in closes {
+            last = st.update(c, 14);
+        }
+        // First RSI is emitted on the 15th close (14 deltas).
+        let rsi = last.expect("RSI after 14 deltas");
+        assert!((rsi - 70.46).abs() < 0.5, "rsi was {}", rsi);
+    }
+
+    #[tokio::test]
+    async fn all_gains_yields_rsi_100() {
+        let mut st = RsiState::new();
+        let mut last = None;
+        for i in 0..5 {
+            last = st.update(10.0 + i as f64, 3);
+        }
+        assert_eq!(last, Some(100.0));
+    }
+
+    #[tokio::test]
+    async fn pipeline_emits_rsi_and_commits_valid_trades() {
+        let payloads: Vec<String> = (0..5).map(|i| trade(10.0 + i as f64, i as u128)).collect();
+        let refs: Vec<&str> = payloads.iter().map(|s| s.as_str()).collect();
+        let mut source = MemoryTradeSource::from_payloads(&refs);
+        let sink = MemoryRsiSink::default();
+        drive(&mut source, &sink, &cfg(3)).await;
+
+        // period=3 => first RSI on the 4th trade (3 deltas), then one per trade.
+        assert_eq!(sink.rsi.lock().unwrap().len(), 2);
+        assert_eq!(source.committed, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_routes_unparseable_to_dlq() {
+        let mut source = MemoryTradeSource::from_payloads(&["not json", "{\"nope\":1}"]);
+        let sink = MemoryRsiSink::default();
+        drive(&mut source, &sink, &cfg(14)).await;
+
+        assert_eq!(sink.dlq.lock().unwrap().len(), 2);
+        assert!(sink.rsi.lock().unwrap().is_empty());
+        // DLQ writes succeeded in the memory sink, so offsets still commit.
+        assert_eq!(source.committed, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_finalizes_candle_on_bucket_rollover() {
+        // two trades in bucket 0, one in bucket 1 => one finalized candle
+        let payloads = [
+            trade(10.0, 1_000),
+            trade(12.0, 2_000),
+            trade(11.0, 61_000),
+        ];
+        let refs: Vec<&str> = payloads.iter().map(|s| s.as_str()).collect();
+        let mut source = MemoryTradeSource::from_payloads(&refs);
+        let sink = MemoryRsiSink::default();
+        drive(&mut source, &sink, &cfg(14)).await;
+
+        let candles = sink.candles.lock().unwrap();
+        assert_eq!(candles.len(), 1);
+        let c = &candles[0];
+        assert_eq!(c.open, 10.0);
+        assert_eq!(c.high, 12.0);
+        assert_eq!(c.low, 10.0);
+        assert_eq!(c.close, 12.0);
+        assert_eq!(c.trade_count, 2);
+    }
+
+    #[tokio::test]
+    async fn pipeline_folds_out_of_order_trade_into_open_candle() {
+        // bucket 0, bucket 1 (opens a new candle), then a late bucket-0
+        // straggler => it must fold into the still-open bucket-1 candle
+        // rather than re-finalizing and reopening bucket 0.
+        let payloads = [
+            trade(10.0, 1_000),
+            trade(12.0, 61_000),
+            trade(9.0, 2_000),
+        ];
+        let refs: Vec<&str> = payloads.iter().map(|s| s.as_str()).collect();
+        let mut source = MemoryTradeSource::from_payloads(&refs);
+        let sink = MemoryRsiSink::default();
+        drive(&mut source, &sink, &cfg(14)).await;
+
+        let candles = sink.candles.lock().unwrap();
+        assert_eq!(candles.len(), 1);
+        let c = &candles[0];
+        assert_eq!(c.open, 10.0);
+        assert_eq!(c.trade_count, 1);
+    }
+}
@@ -0,0 +1,160 @@
+//! Embedded WebSocket server for streaming live RSI to dashboards.
+//!
+//! Mirrors the snapshot-then-delta pattern used by the fills service: on
+//! connect a client receives the entire current RSI map, then every freshly
+//! computed [`RsiMessage`] is forwarded as it is produced. A client may send a
+//! `subscribe` command to restrict the stream to specific `token_address`es.
+
+use crate::RsiMessage;
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Latest RSI per token, shared with the consume loop.
+pub type CheckpointMap = Arc<Mutex<HashMap<String, RsiMessage>>>;
+/// Connected WebSocket clients keyed by peer address.
+pub type PeerMap = Arc<Mutex<HashMap<SocketAddr, Peer>>>;
+
+/// A single connected client: its outbound channel and optional token filter.
+pub struct Peer {
+    tx: UnboundedSender<Message>,
+    /// `None` means "all tokens"; `Some(set)` restricts to those addresses.
+    tokens: Option<HashSet<String>>,
+}
+
+/// Commands a client may send over the socket. Currently only `subscribe`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientCommand {
+    Subscribe { tokens: Vec<String> },
+}
+
+/// Push a freshly computed RSI to the checkpoint map and every interested peer.
+pub fn broadcast(checkpoints: &CheckpointMap, peers: &PeerMap, msg: &RsiMessage) {
+    if let Ok(mut map) = checkpoints.lock() {
+        map.insert(msg.token_address.clone(), msg.clone());
+    }
+
+    let text = match serde_json::to_string(msg) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to serialize RSI message for WS broadcast: {:?}", e);
+            return;
+        }
+    };
+
+    if let Ok(peers) = peers.lock() {
+        for peer in peers.values() {
+            if peer.wants(&msg.token_address) {
+                // Dropped receivers simply error here; the reader task removes them.
+                let _ = peer.tx.send(Message::Text(text.clone()));
+            }
+        }
+    }
+}
+
+impl Peer {
+    fn wants(&self, token: &str) -> bool {
+        match &self.tokens {
+            None => true,
+            Some(set) => set.contains(token),
+        }
+    }
+}
+
+/// Accept connections indefinitely, logging accept errors and continuing.
+/// Returns only if binding fails. Spawns a task per client.
+pub async fn run_server(bind: String, checkpoints: CheckpointMap, peers: PeerMap) {
+    let listener = match TcpListener::bind(&bind).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to bind WebSocket server on {}: {:?}", bind, e);
+            return;
+        }
+    };
+    info!("WebSocket server listening on {}", bind);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let checkpoints = checkpoints.clone();
+                let peers = peers.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, addr, checkpoints, peers).await {
+                        error!("WebSocket connection {} ended with error: {:?}", addr, e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("WebSocket accept error: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: tokio::net::TcpStream,
+    addr: SocketAddr,
+    checkpoints: CheckpointMap,
+    peers: PeerMap,
+) -> anyhow::Result<()> {
+    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    info!("WebSocket client connected: {}", addr);
+
+    let (mut sink, mut source) = ws_stream.split();
+    let (tx, mut rx) = unbounded_channel::<Message>();
+
+    // Register the peer (no filter => all tokens) before sending the snapshot
+    // so no update is missed between the snapshot and the first delta.
+    peers.lock().unwrap().insert(addr, Peer { tx, tokens: None });
+
+    // Initial snapshot of the entire current map.
+    if let Ok(map) = checkpoints.lock() {
+        for msg in map.values() {
+            if let Ok(text) = serde_json::to_string(msg) {
+                let _ = peers
+                    .lock()
+                    .unwrap()
+                    .get(&addr)
+                    .map(|p| p.tx.send(Message::Text(text)));
+            }
+        }
+    }
+
+    // Forward broadcast messages to the socket.
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            if sink.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Handle inbound commands until the client disconnects.
+    while let Some(frame) = source.next().await {
+        match frame {
+            Ok(Message::Text(text)) => {
+                if let Ok(ClientCommand::Subscribe { tokens }) =
+                    serde_json::from_str::<ClientCommand>(&text)
+                {
+                    if let Some(peer) = peers.lock().unwrap().get_mut(&addr) {
+                        peer.tokens = Some(tokens.into_iter().collect());
+                    }
+                }
+            }
+            Ok(Message::Close(_)) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    peers.lock().unwrap().remove(&addr);
+    writer.abort();
+    info!("WebSocket client disconnected: {}", addr);
+    Ok(())
+}